@@ -2,6 +2,7 @@ use crate::workspace::Workspace;
 use failure::{format_err, Error};
 use lucet_idl::{self, Backend, Config, Package};
 use lucet_wasi;
+use lucet_wasi_sdk::Toolchain;
 use lucetc::{Lucetc, LucetcOpts};
 use std::fs::File;
 use std::io::Write;
@@ -44,9 +45,8 @@ int main(int argc, char* argv[]) {
         Ok(())
     }
     fn wasi_clang(&mut self) -> Result<(), Error> {
-        let wasi_sdk =
-            PathBuf::from(std::env::var("WASI_SDK").unwrap_or_else(|_| "/opt/wasi-sdk".to_owned()));
-        let cmd_cc = Command::new(wasi_sdk.join("bin").join("clang"))
+        let toolchain = Toolchain::discover()?;
+        let cmd_cc = Command::new(toolchain.clang())
             .arg("--std=c99")
             .arg(self.work.source_path("main.c"))
             .arg("-I")
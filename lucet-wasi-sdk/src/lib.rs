@@ -1,8 +1,12 @@
-use failure::{Error, Fail};
+use failure::{format_err, Error, Fail};
+use std::collections::hash_map::DefaultHasher;
 use std::env;
-use std::io::Write;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use tempfile::TempDir;
 
 const WASI_TARGET: &str = "wasm32-unknown-wasi";
@@ -18,6 +22,27 @@ pub enum CompileError {
         #[cause]
         e: Error,
     },
+    #[fail(display = "Run error: {}", _0)]
+    Run {
+        #[cause]
+        e: Error,
+    },
+    #[fail(display = "wasi-sdk toolchain component '{}' not found at {}", component, path)]
+    ToolchainComponentMissing { component: String, path: String },
+    #[fail(
+        display = "wasi-sdk toolchain component '{}' is version {}, but this crate requires at least {}",
+        component, found, minimum
+    )]
+    ToolchainVersionTooOld {
+        component: String,
+        found: String,
+        minimum: String,
+    },
+    #[fail(
+        display = "could not determine the version of wasi-sdk toolchain component '{}' from: {}",
+        component, output
+    )]
+    ToolchainVersionUnparseable { component: String, output: String },
     #[fail(display = "IO error: {}", _0)]
     IO {
         #[cause]
@@ -76,6 +101,125 @@ fn wasm_clang() -> PathBuf {
     }
 }
 
+fn wasm_ld() -> PathBuf {
+    match env::var("WASM_LD") {
+        Ok(wasm_ld) => Path::new(&wasm_ld).to_path_buf(),
+        Err(_) => {
+            let mut path = wasi_sdk();
+            path.push("bin");
+            path.push("wasm-ld");
+            path
+        }
+    }
+}
+
+/// The oldest wasi-sdk clang this crate is known to work with.
+const MIN_CLANG_VERSION: (u32, u32) = (8, 0);
+
+/// A located, validated wasi-sdk toolchain: a `clang` and `wasm-ld` binary
+/// plus a wasi sysroot, resolved once from `WASI_SDK`/`CLANG`/`WASM_LD`/
+/// `WASI_SYSROOT` (or the `/opt/wasi-sdk` default) and checked to actually
+/// exist and be new enough, rather than discovered separately by every
+/// `Compile`/`Link` invocation only to fail deep inside a clang subprocess.
+#[derive(Clone, Debug)]
+pub struct Toolchain {
+    clang: PathBuf,
+    sysroot: PathBuf,
+    wasm_ld: PathBuf,
+}
+
+/// Caches the result of `Toolchain::discover` so a whole build - however
+/// many `Compile`/`Link` invocations it involves - runs `clang --version`
+/// at most once rather than once per file compiled or linked.
+static TOOLCHAIN_CACHE: Mutex<Option<Toolchain>> = Mutex::new(None);
+
+impl Toolchain {
+    /// The toolchain for the current process, discovering and validating it
+    /// on first use and reusing the result for every later call. Prefer this
+    /// over `discover` in `Compile`/`Link`/`Lucetc`, which need the same
+    /// toolchain for every file they touch.
+    pub fn resolved() -> Result<Self, CompileError> {
+        let mut cache = TOOLCHAIN_CACHE.lock().unwrap();
+        if let Some(toolchain) = cache.as_ref() {
+            return Ok(toolchain.clone());
+        }
+        let toolchain = Self::discover()?;
+        *cache = Some(toolchain.clone());
+        Ok(toolchain)
+    }
+
+    pub fn discover() -> Result<Self, CompileError> {
+        let clang = wasm_clang();
+        Self::require_exists("clang", &clang)?;
+
+        let sysroot = wasi_sysroot();
+        Self::require_exists("sysroot", &sysroot)?;
+
+        let wasm_ld = wasm_ld();
+        Self::require_exists("wasm-ld", &wasm_ld)?;
+
+        let version = Self::clang_version(&clang)?;
+        if version < MIN_CLANG_VERSION {
+            Err(CompileError::ToolchainVersionTooOld {
+                component: "clang".to_string(),
+                found: format!("{}.{}", version.0, version.1),
+                minimum: format!("{}.{}", MIN_CLANG_VERSION.0, MIN_CLANG_VERSION.1),
+            })?;
+        }
+
+        Ok(Toolchain {
+            clang,
+            sysroot,
+            wasm_ld,
+        })
+    }
+
+    pub fn clang(&self) -> &Path {
+        &self.clang
+    }
+
+    pub fn sysroot(&self) -> &Path {
+        &self.sysroot
+    }
+
+    pub fn wasm_ld(&self) -> &Path {
+        &self.wasm_ld
+    }
+
+    fn require_exists(component: &str, path: &Path) -> Result<(), CompileError> {
+        if path.exists() {
+            Ok(())
+        } else {
+            Err(CompileError::ToolchainComponentMissing {
+                component: component.to_string(),
+                path: path.to_string_lossy().into_owned(),
+            })
+        }
+    }
+
+    fn clang_version(clang: &Path) -> Result<(u32, u32), CompileError> {
+        let output = Command::new(clang).arg("--version").output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Self::parse_clang_version(&stdout).ok_or_else(|| CompileError::ToolchainVersionUnparseable {
+            component: "clang".to_string(),
+            output: stdout.trim().to_string(),
+        })
+    }
+
+    /// Parses the `M.N` out of a `clang --version` first line, e.g.
+    /// `"clang version 8.0.0 (https://...)"` -> `(8, 0)`.
+    fn parse_clang_version(output: &str) -> Option<(u32, u32)> {
+        let version_str = output.lines().next()?.split("version").nth(1)?.trim();
+        let mut parts = version_str
+            .split(|c: char| c != '.' && !c.is_ascii_digit())
+            .next()?
+            .split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().unwrap_or("0").parse().ok()?;
+        Some((major, minor))
+    }
+}
+
 pub struct Compile {
     input: PathBuf,
     cflags: Vec<String>,
@@ -129,20 +273,15 @@ impl Compile {
     }
 
     pub fn compile<P: AsRef<Path>>(&self, output: P) -> Result<(), CompileError> {
-        let clang = wasm_clang();
-        if !clang.exists() {
-            Err(CompileError::FileNotFound(
-                clang.to_string_lossy().into_owned(),
-            ))?;
-        }
+        let toolchain = Toolchain::resolved()?;
         if !self.input.exists() {
             Err(CompileError::FileNotFound(
                 self.input.to_string_lossy().into_owned(),
             ))?;
         }
-        let mut cmd = Command::new(clang);
+        let mut cmd = Command::new(toolchain.clang());
         cmd.arg(format!("--target={}", WASI_TARGET));
-        cmd.arg(format!("--sysroot={}", wasi_sysroot().display()));
+        cmd.arg(format!("--sysroot={}", toolchain.sysroot().display()));
         cmd.arg("-c");
         cmd.arg(self.input.clone());
         cmd.arg("-o");
@@ -160,6 +299,9 @@ pub struct Link {
     cflags: Vec<String>,
     ldflags: Vec<String>,
     print_output: bool,
+    linker_flavor: LinkerFlavor,
+    cache_dir: Option<PathBuf>,
+    jobs: usize,
 }
 
 impl Link {
@@ -169,6 +311,9 @@ impl Link {
             cflags: vec![],
             ldflags: vec![],
             print_output: false,
+            linker_flavor: LinkerFlavor::for_target(WASI_TARGET),
+            cache_dir: None,
+            jobs: 1,
         }
         .with_link_opt(LinkOpt::DefaultOpts)
     }
@@ -182,14 +327,28 @@ impl Link {
         self
     }
 
+    /// Compile each input to its own object file, cached in `dir` by a hash
+    /// of its contents and cflags, before linking the objects together.
+    /// Without a cache directory, `link` compiles and links every input in
+    /// a single clang invocation on every call.
+    pub fn with_cache_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.cache_dir = Some(PathBuf::from(dir.as_ref()));
+        self
+    }
+
+    /// The number of worker threads used to compile inputs when a cache
+    /// directory is set. Has no effect otherwise.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
     pub fn link<P: AsRef<Path>>(&self, output: P) -> Result<(), CompileError> {
-        let clang = wasm_clang();
-        if !clang.exists() {
-            Err(CompileError::FileNotFound(
-                clang.to_string_lossy().into_owned(),
-            ))?;
+        if let Some(cache_dir) = self.cache_dir.clone() {
+            return self.link_cached(&cache_dir, output);
         }
-        let mut cmd = Command::new(clang);
+        let toolchain = Toolchain::resolved()?;
+        let mut cmd = Command::new(toolchain.clang());
         for input in self.input.iter() {
             if !input.exists() {
                 Err(CompileError::FileNotFound(
@@ -209,6 +368,125 @@ impl Link {
         let run = cmd.output().expect("clang executable exists");
         CompileError::check(run, self.print_output)
     }
+
+    /// Compile every input to a cached object file on a fixed-size pool of
+    /// worker threads, then link the resulting objects. The link step waits
+    /// on every compile job and propagates the first `CompileError` any of
+    /// them hit.
+    fn link_cached<P: AsRef<Path>>(
+        &self,
+        cache_dir: &Path,
+        output: P,
+    ) -> Result<(), CompileError> {
+        std::fs::create_dir_all(cache_dir)?;
+
+        let queue = Arc::new(Mutex::new((0..self.input.len()).rev().collect::<Vec<_>>()));
+        let objects = Arc::new(Mutex::new(vec![None; self.input.len()]));
+        let error = Arc::new(Mutex::new(None));
+
+        let jobs = self.jobs.min(self.input.len().max(1));
+        let handles: Vec<_> = (0..jobs)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let objects = Arc::clone(&objects);
+                let error = Arc::clone(&error);
+                let inputs = self.input.clone();
+                let cflags = self.cflags.clone();
+                let print_output = self.print_output;
+                let cache_dir = cache_dir.to_path_buf();
+
+                thread::spawn(move || loop {
+                    if error.lock().unwrap().is_some() {
+                        return;
+                    }
+                    let index = match queue.lock().unwrap().pop() {
+                        Some(index) => index,
+                        None => return,
+                    };
+                    match Self::compile_cached(&inputs[index], &cflags, print_output, &cache_dir)
+                    {
+                        Ok(obj_file) => objects.lock().unwrap()[index] = Some(obj_file),
+                        Err(e) => {
+                            error.lock().unwrap().get_or_insert(e);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().expect("compile worker thread panicked");
+        }
+
+        if let Some(e) = error.lock().unwrap().take() {
+            return Err(e);
+        }
+
+        let objects: Vec<PathBuf> = objects
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|o| o.expect("every queued input was compiled"))
+            .collect();
+
+        let linker = Link {
+            input: objects,
+            // `compile_cached` only used these cflags to compile each input
+            // to an object above; the final link still needs link-relevant
+            // ones among them (e.g. `-nostartfiles`, `-nostdlib`, `-L`/`-l`).
+            cflags: self.cflags.clone(),
+            ldflags: self.ldflags.clone(),
+            print_output: self.print_output,
+            linker_flavor: self.linker_flavor,
+            cache_dir: None,
+            jobs: 1,
+        };
+        linker.link(output)
+    }
+
+    /// Compile `input` to an object file cached under `cache_dir`, keyed by
+    /// a hash of its path (as given, not canonicalized), contents, and
+    /// `cflags`, so that changing any
+    /// of them forces a recompile. Hashing the path (rather than e.g. the
+    /// input's position in the input list) keeps object filenames
+    /// collision-free without making the cache sensitive to input order.
+    fn compile_cached(
+        input: &Path,
+        cflags: &[String],
+        print_output: bool,
+        cache_dir: &Path,
+    ) -> Result<PathBuf, CompileError> {
+        let contents = std::fs::read(input)?;
+        let mut hasher = DefaultHasher::new();
+        input.hash(&mut hasher);
+        contents.hash(&mut hasher);
+        cflags.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let stem = input
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("input");
+        let obj_file = cache_dir.join(format!("{}-{:016x}.o", stem, key));
+
+        if obj_file.exists() {
+            return Ok(obj_file);
+        }
+
+        let mut compile = Compile::new(input);
+        for cflag in cflags {
+            compile.cflag(cflag);
+        }
+        compile.print_output(print_output);
+
+        // Compile to a sibling path and rename into place, so a concurrent
+        // reader of the cache never observes a partially-written object.
+        let tmp_obj_file = cache_dir.join(format!("{}-{:016x}.o.tmp", stem, key));
+        compile.compile(&tmp_obj_file)?;
+        std::fs::rename(&tmp_obj_file, &obj_file)?;
+
+        Ok(obj_file)
+    }
 }
 
 pub trait AsLink {
@@ -221,6 +499,35 @@ impl AsLink for Link {
     }
 }
 
+/// Which linker's command-line syntax `LinkOpt::as_ldflags` should emit.
+///
+/// wasi-sdk's clang is a cross compiler: the flags it accepts for
+/// `-Wl,...` depend on which linker it was built to drive, not on the host
+/// `Link` happens to run on. Selecting the flavor explicitly (rather than
+/// branching on `cfg(target_os)`) is what makes cross-building possible.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkerFlavor {
+    /// LLVM's `wasm-ld`, selected when targeting `wasm32-unknown-wasi`
+    WasmLd,
+    /// Apple's `ld64`, selected when targeting a macOS host binary
+    Ld64,
+    /// A GNU `ld`-compatible linker
+    Gnu,
+}
+
+impl LinkerFlavor {
+    /// The flavor clang would select when driving the linker for `target`.
+    fn for_target(target: &str) -> Self {
+        if target.starts_with("wasm32") {
+            LinkerFlavor::WasmLd
+        } else if target.contains("darwin") || target.contains("macos") {
+            LinkerFlavor::Ld64
+        } else {
+            LinkerFlavor::Gnu
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum LinkOpt<'t> {
     /// Allow references to an undefined function that will be resolved later by the dynamic linker
@@ -249,11 +556,31 @@ pub enum LinkOpt<'t> {
 
     /// Remove functions and data that are unreachable by the entry point or exported symbols
     StripUnused,
+
+    /// The initial size, in bytes, of the guest's linear memory
+    InitialMemory(u32),
+
+    /// The maximum size, in bytes, the guest's linear memory is allowed to grow to
+    MaxMemory(u32),
+
+    /// The size, in bytes, reserved for the guest's stack
+    StackSize(u32),
+
+    /// The address at which static data is laid out, below which the stack and
+    /// any other unallocated linear memory lives
+    GlobalBase(u32),
 }
 
 impl<'t> LinkOpt<'t> {
-    #[cfg(target_os = "macos")]
-    fn as_ldflags(&self) -> Vec<String> {
+    fn as_ldflags(&self, flavor: LinkerFlavor) -> Vec<String> {
+        match flavor {
+            LinkerFlavor::Ld64 => self.as_ld64_ldflags(),
+            LinkerFlavor::WasmLd => self.as_wasm_ld_ldflags(),
+            LinkerFlavor::Gnu => self.as_gnu_ldflags(),
+        }
+    }
+
+    fn as_ld64_ldflags(&self) -> Vec<String> {
         match self {
             LinkOpt::AllowUndefined(_symbol) => vec![],
             LinkOpt::AllowUndefinedAll => vec!["-undefined,dynamic_lookup".to_string()],
@@ -264,11 +591,15 @@ impl<'t> LinkOpt<'t> {
             LinkOpt::Shared => vec!["-dylib".to_string()],
             LinkOpt::StripDebug => vec!["-S".to_string()],
             LinkOpt::StripUnused => vec!["-dead_strip".to_string()],
+            // ld64 has no concept of wasm linear memory; these are no-ops.
+            LinkOpt::InitialMemory(_)
+            | LinkOpt::MaxMemory(_)
+            | LinkOpt::StackSize(_)
+            | LinkOpt::GlobalBase(_) => vec![],
         }
     }
 
-    #[cfg(not(target_os = "macos"))]
-    fn as_ldflags(&self) -> Vec<String> {
+    fn as_wasm_ld_ldflags(&self) -> Vec<String> {
         match self {
             LinkOpt::AllowUndefined(symbol) => vec![format!("-U,_{}", symbol).to_string()],
             LinkOpt::AllowUndefinedAll => vec!["--allow-undefined".to_string()],
@@ -279,6 +610,33 @@ impl<'t> LinkOpt<'t> {
             LinkOpt::Shared => vec!["--shared".to_string()],
             LinkOpt::StripDebug => vec!["-S".to_string()],
             LinkOpt::StripUnused => vec!["--strip-discarded".to_string()],
+            LinkOpt::InitialMemory(bytes) => vec![format!("--initial-memory={}", bytes)],
+            LinkOpt::MaxMemory(bytes) => vec![format!("--max-memory={}", bytes)],
+            LinkOpt::StackSize(bytes) => vec!["-z".to_string(), format!("stack-size={}", bytes)],
+            LinkOpt::GlobalBase(addr) => vec![format!("--global-base={}", addr)],
+        }
+    }
+
+    /// Flags for a GNU `ld`-compatible linker. wasi-sdk's clang never selects
+    /// this flavor on its own (it always targets `wasm-ld` or `ld64`), but it
+    /// is available for hosts that link through a GNU-compatible driver.
+    fn as_gnu_ldflags(&self) -> Vec<String> {
+        match self {
+            LinkOpt::AllowUndefined(_symbol) => vec!["--unresolved-symbols=ignore-all".to_string()],
+            LinkOpt::AllowUndefinedAll => vec!["--unresolved-symbols=ignore-all".to_string()],
+            LinkOpt::DefaultOpts => vec![],
+            LinkOpt::Export(symbol) => vec![format!("--export-dynamic-symbol={}", symbol)],
+            LinkOpt::ExportAll => vec!["--export-dynamic".to_string()],
+            LinkOpt::NoDefaultEntryPoint => vec!["-e0".to_string()],
+            LinkOpt::Shared => vec!["-shared".to_string()],
+            LinkOpt::StripDebug => vec!["-S".to_string()],
+            LinkOpt::StripUnused => vec!["--gc-sections".to_string()],
+            // These describe wasm-ld's linear-memory layout, which a GNU ld
+            // target has no equivalent of; these are no-ops.
+            LinkOpt::InitialMemory(_)
+            | LinkOpt::MaxMemory(_)
+            | LinkOpt::StackSize(_)
+            | LinkOpt::GlobalBase(_) => vec![],
         }
     }
 }
@@ -289,11 +647,15 @@ pub trait LinkOpts {
 
     fn export<S: AsRef<str>>(&mut self, export: S);
     fn with_export<S: AsRef<str>>(self, export: S) -> Self;
+
+    fn linker_flavor(&mut self, flavor: LinkerFlavor);
+    fn with_linker_flavor(self, flavor: LinkerFlavor) -> Self;
 }
 
 impl<T: AsLink> LinkOpts for T {
     fn link_opt(&mut self, link_opt: LinkOpt) {
-        self.as_link().ldflags.extend(link_opt.as_ldflags());
+        let flavor = self.as_link().linker_flavor;
+        self.as_link().ldflags.extend(link_opt.as_ldflags(flavor));
     }
 
     fn with_link_opt(mut self, link_opt: LinkOpt) -> Self {
@@ -305,6 +667,15 @@ impl<T: AsLink> LinkOpts for T {
         self.link_opt(LinkOpt::Export(export.as_ref()));
     }
 
+    fn linker_flavor(&mut self, flavor: LinkerFlavor) {
+        self.as_link().linker_flavor = flavor;
+    }
+
+    fn with_linker_flavor(mut self, flavor: LinkerFlavor) -> Self {
+        self.linker_flavor(flavor);
+        self
+    }
+
     fn with_export<S: AsRef<str>>(mut self, export: S) -> Self {
         self.export(export);
         self
@@ -333,11 +704,25 @@ impl<T: AsLink> CompileOpts for T {
     }
 }
 
+/// The kinds of build artifact a [`Lucetc`] run can be asked to emit,
+/// in addition to the final shared object that `build` writes to `output`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArtifactKind {
+    /// The relocatable wasm module linked from all inputs
+    Wasm,
+    /// The object file compiled from the input at this index
+    Object(usize),
+    /// The final lucet shared object, the same one written to `build`'s `output`
+    SharedObject,
+}
+
 pub struct Lucetc {
     link: Link,
     lucetc: lucetc::Lucetc,
     tmpdir: TempDir,
     wasm_file: PathBuf,
+    keep_temps: bool,
+    artifacts: Vec<(ArtifactKind, PathBuf)>,
 }
 
 impl Lucetc {
@@ -351,6 +736,8 @@ impl Lucetc {
             lucetc,
             tmpdir,
             wasm_file,
+            keep_temps: false,
+            artifacts: vec![],
         }
     }
 
@@ -359,12 +746,103 @@ impl Lucetc {
         self
     }
 
+    /// See [`Link::with_cache_dir`].
+    pub fn with_cache_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.link = self.link.with_cache_dir(dir);
+        self
+    }
+
+    /// See [`Link::with_jobs`].
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.link = self.link.with_jobs(jobs);
+        self
+    }
+
+    /// Also write the artifact of `kind` to `output` when `build` runs.
+    pub fn emit<P: AsRef<Path>>(mut self, kind: ArtifactKind, output: P) -> Self {
+        self.artifacts.push((kind, PathBuf::from(output.as_ref())));
+        self
+    }
+
+    /// Keep the intermediate wasm module and object files in the build's
+    /// temporary directory instead of discarding them once `build` returns.
+    /// The only object files in that directory are those compiled from
+    /// source inputs for an [`ArtifactKind::Object`] request when no cache
+    /// directory is set; with a cache directory, objects live there instead.
+    /// The directory can be located with [`Lucetc::temp_dir`] before `build`
+    /// consumes `self`.
+    pub fn keep_temps(mut self, keep: bool) -> Self {
+        self.keep_temps = keep;
+        self
+    }
+
+    pub fn temp_dir(&self) -> &Path {
+        self.tmpdir.path()
+    }
+
+    fn emit_object<P: AsRef<Path>>(&self, index: usize, dest: P) -> Result<(), CompileError> {
+        let input = self.link.input.get(index).ok_or_else(|| {
+            CompileError::FileNotFound(format!("no input at index {}", index))
+        })?;
+
+        // `Link`/`Lucetc` accept inputs that are already object files (see
+        // `compile_a`/`compile_b` feeding `.o`s straight into `Link::new`),
+        // so only run those through `clang -c` again if they're source.
+        if input.extension().and_then(|ext| ext.to_str()) == Some("o") {
+            std::fs::copy(input, dest)?;
+            return Ok(());
+        }
+
+        // `link` already compiled (and cached) this exact input+cflags
+        // combination when a cache directory is set; look that object up
+        // instead of compiling it again from scratch.
+        if let Some(cache_dir) = self.link.cache_dir.clone() {
+            let obj_file =
+                Link::compile_cached(input, &self.link.cflags, self.link.print_output, &cache_dir)?;
+            std::fs::copy(&obj_file, dest)?;
+            return Ok(());
+        }
+
+        let obj_file = self.tmpdir.path().join(format!("{}.o", index));
+        let mut compile = Compile::new(input);
+        for cflag in self.link.cflags.iter() {
+            compile.cflag(cflag);
+        }
+        compile.print_output(self.link.print_output);
+        compile.compile(&obj_file)?;
+        std::fs::copy(&obj_file, dest)?;
+        Ok(())
+    }
+
     pub fn build<P: AsRef<Path>>(self, output: P) -> Result<(), CompileError> {
         self.link.link(&self.wasm_file)?;
+
+        for (kind, dest) in self.artifacts.iter() {
+            match kind {
+                ArtifactKind::Wasm => {
+                    std::fs::copy(&self.wasm_file, dest)?;
+                }
+                ArtifactKind::Object(index) => self.emit_object(*index, dest)?,
+                ArtifactKind::SharedObject => (),
+            }
+        }
+
         self.lucetc
             .shared_object_file(output.as_ref())
             .map_err(|e| CompileError::Lucetc { e })?;
-        Ok(self.tmpdir.close()?)
+
+        for (kind, dest) in self.artifacts.iter() {
+            if let ArtifactKind::SharedObject = kind {
+                std::fs::copy(output.as_ref(), dest)?;
+            }
+        }
+
+        if self.keep_temps {
+            let _ = self.tmpdir.into_path();
+            Ok(())
+        } else {
+            Ok(self.tmpdir.close()?)
+        }
     }
 }
 
@@ -380,14 +858,183 @@ impl lucetc::AsLucetc for Lucetc {
     }
 }
 
+/// The result of running a guest program to completion: its captured
+/// standard streams and the exit status it terminated with.
+pub struct RunResult {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: i32,
+}
+
+/// Loads a `.so` produced by [`Lucetc::build`] and runs it as a lucet-wasi
+/// guest, capturing its standard streams so tests can assert on program
+/// output rather than just on the existence of the build artifact.
+pub struct Run {
+    so_file: PathBuf,
+    entrypoint: String,
+    args: Vec<String>,
+}
+
+impl Run {
+    pub fn new<P: AsRef<Path>>(so_file: P) -> Self {
+        Run {
+            so_file: PathBuf::from(so_file.as_ref()),
+            entrypoint: "_start".to_owned(),
+            args: vec![],
+        }
+    }
+
+    pub fn entrypoint<S: Into<String>>(mut self, entrypoint: S) -> Self {
+        self.entrypoint = entrypoint.into();
+        self
+    }
+
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn run(&self) -> Result<RunResult, CompileError> {
+        if !self.so_file.exists() {
+            Err(CompileError::FileNotFound(
+                self.so_file.to_string_lossy().into_owned(),
+            ))?;
+        }
+
+        // Force the wasi hostcall symbols to stay linked into (and exported
+        // from) this binary; otherwise the guest's wasi imports have nothing
+        // to resolve against at load/instantiation time.
+        lucet_wasi::export_wasi_funcs();
+        lucet_runtime::lucet_internal_ensure_linked();
+
+        let module = lucet_runtime::DlModule::load(&self.so_file)
+            .map_err(|e| CompileError::Run { e: format_err!("{}", e) })?;
+
+        let region = lucet_runtime::MmapRegion::create(1, &lucet_runtime::Limits::default())
+            .map_err(|e| CompileError::Run { e: format_err!("{}", e) })?;
+
+        let mut stdout = tempfile::tempfile()?;
+        let mut stderr = tempfile::tempfile()?;
+
+        let wasi_ctx = lucet_wasi::WasiCtxBuilder::new()
+            .args(std::iter::once(self.entrypoint.clone()).chain(self.args.clone()))
+            .stdout(stdout.try_clone()?)
+            .stderr(stderr.try_clone()?)
+            .build()
+            .map_err(|e| CompileError::Run { e: format_err!("{}", e) })?;
+
+        let mut inst = region
+            .new_instance_builder(module)
+            .with_embed_ctx(wasi_ctx)
+            .build()
+            .map_err(|e| CompileError::Run { e: format_err!("{}", e) })?;
+
+        let exit_code = match inst.run(self.entrypoint.as_str(), &[]) {
+            Ok(_) => 0,
+            // `proc_exit` terminates the instance rather than returning, and
+            // lucet-wasi surfaces the status it was given through the
+            // termination details' provided payload rather than a dedicated
+            // `Error`/`TerminationDetails` variant, so downcast for it
+            // instead of matching a variant that doesn't carry it. The
+            // payload is the guest's `__wasi_exitcode_t`, which is a `u32`,
+            // not a signed `i32`.
+            Err(lucet_runtime::Error::RuntimeTerminated(details)) => details
+                .provided_details()
+                .and_then(|provided| provided.downcast_ref::<u32>().copied())
+                .ok_or_else(|| CompileError::Run {
+                    e: format_err!("guest terminated without a wasi exit code: {:?}", details),
+                })? as i32,
+            Err(e) => Err(CompileError::Run { e: format_err!("{}", e) })?,
+        };
+
+        let mut stdout_buf = Vec::new();
+        stdout.seek(SeekFrom::Start(0))?;
+        stdout.read_to_end(&mut stdout_buf)?;
+
+        let mut stderr_buf = Vec::new();
+        stderr.seek(SeekFrom::Start(0))?;
+        stderr.read_to_end(&mut stderr_buf)?;
+
+        Ok(RunResult {
+            stdout: stdout_buf,
+            stderr: stderr_buf,
+            exit_code,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lucetc::LucetcOpts;
     use tempfile::TempDir;
+    #[test]
+    fn link_opt_dispatches_on_flavor() {
+        assert_eq!(
+            LinkOpt::Export("foo").as_ldflags(LinkerFlavor::WasmLd),
+            vec!["--export=foo".to_string()]
+        );
+        assert_eq!(
+            LinkOpt::Export("foo").as_ldflags(LinkerFlavor::Ld64),
+            vec!["-exported_symbol,foo".to_string()]
+        );
+        assert_eq!(
+            LinkOpt::Export("foo").as_ldflags(LinkerFlavor::Gnu),
+            vec!["--export-dynamic-symbol=foo".to_string()]
+        );
+    }
+
+    #[test]
+    fn memory_layout_opts_lower_for_wasm_ld() {
+        assert_eq!(
+            LinkOpt::InitialMemory(65536).as_ldflags(LinkerFlavor::WasmLd),
+            vec!["--initial-memory=65536".to_string()]
+        );
+        assert_eq!(
+            LinkOpt::MaxMemory(16777216).as_ldflags(LinkerFlavor::WasmLd),
+            vec!["--max-memory=16777216".to_string()]
+        );
+        assert_eq!(
+            LinkOpt::StackSize(1048576).as_ldflags(LinkerFlavor::WasmLd),
+            vec!["-z".to_string(), "stack-size=1048576".to_string()]
+        );
+        assert_eq!(
+            LinkOpt::GlobalBase(1024).as_ldflags(LinkerFlavor::WasmLd),
+            vec!["--global-base=1024".to_string()]
+        );
+    }
+
+    #[test]
+    fn memory_layout_opts_are_noops_for_ld64() {
+        assert!(LinkOpt::InitialMemory(65536)
+            .as_ldflags(LinkerFlavor::Ld64)
+            .is_empty());
+        assert!(LinkOpt::StackSize(1048576)
+            .as_ldflags(LinkerFlavor::Ld64)
+            .is_empty());
+    }
+
     #[test]
     fn wasi_sdk_installed() {
-        let clang = wasm_clang();
-        assert!(clang.exists(), "clang executable exists");
+        let toolchain = Toolchain::discover().expect("wasi-sdk toolchain discovered");
+        assert!(toolchain.clang().exists(), "clang executable exists");
+        assert!(toolchain.sysroot().exists(), "sysroot exists");
+        assert!(toolchain.wasm_ld().exists(), "wasm-ld executable exists");
+    }
+
+    #[test]
+    fn parse_clang_version() {
+        assert_eq!(
+            Toolchain::parse_clang_version(
+                "clang version 8.0.1 (https://github.com/llvm/llvm-project abcdef)"
+            ),
+            Some((8, 0))
+        );
+        assert_eq!(
+            Toolchain::parse_clang_version("clang version 12.0.0"),
+            Some((12, 0))
+        );
+        assert_eq!(Toolchain::parse_clang_version("not clang at all"), None);
     }
 
     fn test_file(name: &str) -> PathBuf {
@@ -474,4 +1121,97 @@ mod tests {
 
         assert!(so_file.exists(), "so file created");
     }
+
+    #[test]
+    fn compile_a_and_b_cached() {
+        let tmp = TempDir::new().expect("create temporary directory");
+        let cache_dir = tmp.path().join("cache");
+
+        let mut linker = Link::new(&[test_file("a.c"), test_file("b.c")])
+            .with_cache_dir(&cache_dir)
+            .with_jobs(2);
+        linker.cflag("-nostartfiles");
+        linker.link_opt(LinkOpt::NoDefaultEntryPoint);
+
+        let wasmfile = tmp.path().join("ab.wasm");
+
+        linker.link(&wasmfile).expect("link ab.wasm");
+        assert!(wasmfile.exists(), "wasm file created");
+
+        let objects_after_first_link: Vec<_> = std::fs::read_dir(&cache_dir)
+            .expect("read cache dir")
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .collect();
+        assert_eq!(objects_after_first_link.len(), 2, "one object file per input");
+
+        // Linking again with the same inputs and cflags must not recompile.
+        linker.link(&wasmfile).expect("re-link ab.wasm from cache");
+        let objects_after_second_link: Vec<_> = std::fs::read_dir(&cache_dir)
+            .expect("read cache dir")
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .collect();
+        assert_eq!(
+            objects_after_first_link, objects_after_second_link,
+            "cache hit reuses the same object files"
+        );
+    }
+
+    #[test]
+    fn emit_intermediate_artifacts() {
+        let tmp = TempDir::new().expect("create temporary directory");
+
+        let wasm_file = tmp.path().join("ab.wasm");
+        let a_obj = tmp.path().join("a.o");
+        let b_obj = tmp.path().join("b.o");
+
+        let mut lucetc = Lucetc::new(&[test_file("a.c"), test_file("b.c")])
+            .emit(ArtifactKind::Wasm, &wasm_file)
+            .emit(ArtifactKind::Object(0), &a_obj)
+            .emit(ArtifactKind::Object(1), &b_obj)
+            .keep_temps(true);
+        lucetc.cflag("-nostartfiles");
+        lucetc.link_opt(LinkOpt::NoDefaultEntryPoint);
+
+        let so_file = tmp.path().join("ab.so");
+
+        lucetc.build(&so_file).expect("compile ab.so");
+
+        assert!(so_file.exists(), "so file created");
+        assert!(wasm_file.exists(), "intermediate wasm module emitted");
+        assert!(a_obj.exists(), "a.o object file emitted");
+        assert!(b_obj.exists(), "b.o object file emitted");
+    }
+
+    #[test]
+    fn compile_and_run_hello() {
+        let tmp = TempDir::new().expect("create temporary directory");
+
+        let toolchain = Toolchain::resolved().expect("wasi-sdk toolchain discovered");
+        let imports_file = toolchain
+            .sysroot()
+            .join("share")
+            .join("misc")
+            .join("wasm32-wasi.imports");
+
+        let mut lucetc = Lucetc::new(&[test_file("hello.c")]).with_bindings(lucet_wasi::bindings());
+        lucetc.cflag(format!(
+            "-Wl,--allow-undefined-file={}",
+            imports_file.display()
+        ));
+
+        let so_file = tmp.path().join("hello.so");
+
+        lucetc.build(&so_file).expect("compile hello.so");
+
+        let result = Run::new(&so_file).run().expect("run hello.so");
+
+        assert_eq!(result.exit_code, 0, "hello.so exits cleanly");
+        assert_eq!(
+            String::from_utf8_lossy(&result.stdout),
+            "hello, world\n",
+            "hello.so prints its greeting to stdout"
+        );
+    }
 }